@@ -6,7 +6,7 @@ use crate::{
     loader::get_app_data_by_name,
     mm::{translated_refmut, translated_str},
     task::{
-        add_task, current_task, current_user_token, exit_current_and_run_next,
+        add_task, current_task, current_user_token, exit_current_and_run_next, munmap_range,
         suspend_current_and_run_next, TaskStatus,
     },
 };
@@ -110,7 +110,8 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child PCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        *translated_refmut(inner.memory_set.exclusive_access().token(), exit_code_ptr) =
+            exit_code;
         found_pid as isize
     } else {
         -2
@@ -168,22 +169,19 @@ pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
     permission.set(crate::mm::MapPermission::U, true);
     //TCB
     let tcb = current_task().unwrap();
-    let mut inner = tcb.inner.exclusive_access();
+    let inner = tcb.inner.exclusive_access();
+    let mut memory_set = inner.memory_set.exclusive_access();
     let start_vpn: crate::mm::VirtPageNum =
         (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
     let end_vpn: crate::mm::VirtPageNum =
         (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
     let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
     for vpn in vpn_range {
-        if inner.memory_set.translate(vpn).is_some()
-            && inner.memory_set.translate(vpn).unwrap().bits != 0
-        {
+        if memory_set.translate(vpn).is_some() && memory_set.translate(vpn).unwrap().bits != 0 {
             return -1;
         }
     }
-    inner
-        .memory_set
-        .insert_framed_area(_start.into(), (_start + _len).into(), permission);
+    memory_set.insert_framed_area(_start.into(), (_start + _len).into(), permission);
     return 0;
 }
 
@@ -195,30 +193,14 @@ pub fn sys_munmap(_start: usize, _len: usize) -> isize {
     };
     //TCB
     let tcb = current_task().unwrap();
-    let mut inner = tcb.inner.exclusive_access();
+    let inner = tcb.inner.exclusive_access();
+    let mut memory_set = inner.memory_set.exclusive_access();
     let start_vpn: crate::mm::VirtPageNum =
         (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
     let end_vpn: crate::mm::VirtPageNum =
         (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
     let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
-    //检查是否无映射
-    let mut id = 0;
-    let mut flag = -1;
-    for (index, area) in inner.memory_set.areas.iter().enumerate() {
-        if area.vpn_range.get_start() == vpn_range.get_start()
-            && area.vpn_range.get_end() == vpn_range.get_end()
-        {
-            id = index;
-            flag = 0;
-        }
-    }
-    if flag == -1 {
-        return -1;
-    }
-    let ms: &mut crate::mm::MemorySet = &mut inner.memory_set;
-    ms.areas[id].unmap(&mut ms.page_table);
-    inner.memory_set.areas.remove(id);
-    return 0;
+    munmap_range(&mut memory_set, vpn_range)
 }
 
 /// change data segment size
@@ -249,6 +231,26 @@ pub fn sys_spawn(_path: *const u8) -> isize {
     }
 }
 
+/// Spawn a new thread sharing the caller's address space, starting at
+/// `entry` with `arg` passed through as its first argument. Returns the new
+/// thread's tid.
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_thread_create",
+        current_task().unwrap().pid.0
+    );
+    let task = current_task().unwrap();
+    let new_task = task.create_thread(entry, arg);
+    let new_tid = new_task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid;
+    add_task(new_task);
+    new_tid as isize
+}
+
 // YOUR JOB: Set task priority.
 pub fn sys_set_priority(_prio: isize) -> isize {
     trace!(
@@ -260,6 +262,8 @@ pub fn sys_set_priority(_prio: isize) -> isize {
     }
     let task = current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
-    task_inner.task_priority = _prio;
+    // recompute `pass` too, otherwise stride scheduling would keep using the
+    // old priority until the next time this task happens to be recreated
+    task_inner.set_priority(_prio);
     _prio
 }
@@ -0,0 +1,35 @@
+//! Implementation of [`TaskContext`]
+
+/// Task Context
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TaskContext {
+    /// return address ( e.g. __restore ) of __switch ASM function
+    ra: usize,
+    /// kernel stack pointer of app
+    sp: usize,
+    /// callee saved registers:  s 0..11
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    /// init task context
+    pub fn zero_init() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+    /// set Task Context{__restore ASM funciton entry, kernel stack pointer, s_0..12 }
+    pub fn goto_trap_return(kstack_ptr: usize) -> Self {
+        extern "C" {
+            fn __restore();
+        }
+        Self {
+            ra: __restore as usize,
+            sp: kstack_ptr,
+            s: [0; 12],
+        }
+    }
+}
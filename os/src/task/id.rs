@@ -0,0 +1,157 @@
+//! Thread id allocation and the per-thread resources (user stack + trap
+//! context page) carved out of a process's shared [`MemorySet`].
+use super::TaskControlBlock;
+use crate::config::{PAGE_SIZE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
+use crate::mm::{MapPermission, PhysPageNum, VirtAddr};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// A simple id allocator that recycles freed ids instead of only ever
+/// counting up; used for tids (pids come from [`super::pid`]'s own
+/// `PidAllocator`).
+pub struct RecycleAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl RecycleAllocator {
+    /// Create an empty `RecycleAllocator`
+    pub fn new() -> Self {
+        RecycleAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        }
+    }
+    /// Allocate an id
+    pub fn alloc(&mut self) -> usize {
+        if let Some(id) = self.recycled.pop() {
+            id
+        } else {
+            self.current += 1;
+            self.current - 1
+        }
+    }
+    /// Recycle an id
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
+        assert!(
+            !self.recycled.iter().any(|i| *i == id),
+            "id {} has been deallocated!",
+            id
+        );
+        self.recycled.push(id);
+    }
+}
+
+/// Virtual address of the bottom of thread `tid`'s user stack, given the
+/// address space's `ustack_base`. Each thread's stack is `USER_STACK_SIZE`
+/// bytes with a `PAGE_SIZE` guard gap below the next one.
+pub fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
+    ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
+}
+
+/// Virtual address of the bottom of thread `tid`'s one-page trap context,
+/// counting down from `TRAP_CONTEXT_BASE` so every thread gets its own page.
+pub fn trap_cx_bottom_from_tid(tid: usize) -> usize {
+    TRAP_CONTEXT_BASE - tid * PAGE_SIZE
+}
+
+/// A thread's private resources within its process's shared address space:
+/// its tid, its user stack, and its trap context page. Mapped on creation,
+/// unmapped on `Drop` (i.e. when the owning [`TaskControlBlock`] is reaped).
+pub struct TaskUserRes {
+    /// This thread's id, unique within its process
+    pub tid: usize,
+    /// The virtual address from which every thread's user stack in this
+    /// process is offset by `tid`
+    pub ustack_base: usize,
+    /// The process (i.e. the task that owns the shared `memory_set`) this
+    /// thread's resources are mapped into
+    pub process: Weak<TaskControlBlock>,
+}
+
+impl TaskUserRes {
+    /// Allocate a tid from `process` and, if `alloc_user_res` is set, map
+    /// this thread's user stack and trap context into its address space.
+    pub fn new(process: &Arc<TaskControlBlock>, ustack_base: usize, alloc_user_res: bool) -> Self {
+        let tid = process.inner_exclusive_access().alloc_tid();
+        let task_user_res = Self {
+            tid,
+            ustack_base,
+            process: Arc::downgrade(process),
+        };
+        if alloc_user_res {
+            task_user_res.alloc_user_res();
+        }
+        task_user_res
+    }
+
+    /// Map this thread's user stack and trap context page into the shared
+    /// `memory_set`.
+    pub fn alloc_user_res(&self) {
+        let process = self.process.upgrade().unwrap();
+        let inner = process.inner_exclusive_access();
+        let mut memory_set = inner.memory_set.exclusive_access();
+
+        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
+        let ustack_top = ustack_bottom + USER_STACK_SIZE;
+        memory_set.insert_framed_area(
+            ustack_bottom.into(),
+            ustack_top.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+
+        let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
+        let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+        memory_set.insert_framed_area(
+            trap_cx_bottom.into(),
+            trap_cx_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+    }
+
+    /// Unmap this thread's user stack and trap context page, freeing the
+    /// underlying frames.
+    pub fn dealloc_user_res(&self) {
+        let process = self.process.upgrade().unwrap();
+        let inner = process.inner_exclusive_access();
+        let mut memory_set = inner.memory_set.exclusive_access();
+
+        let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(self.ustack_base, self.tid).into();
+        memory_set.remove_area_with_start_vpn(ustack_bottom_va.into());
+
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
+        memory_set.remove_area_with_start_vpn(trap_cx_bottom_va.into());
+    }
+
+    /// The virtual address of this thread's trap context page
+    pub fn trap_cx_user_va(&self) -> usize {
+        trap_cx_bottom_from_tid(self.tid)
+    }
+
+    /// The physical page this thread's trap context is mapped to
+    pub fn trap_cx_ppn(&self) -> PhysPageNum {
+        let process = self.process.upgrade().unwrap();
+        let inner = process.inner_exclusive_access();
+        inner
+            .memory_set
+            .exclusive_access()
+            .translate(VirtAddr::from(self.trap_cx_user_va()).into())
+            .unwrap()
+            .ppn()
+    }
+
+    /// The top of this thread's user stack
+    pub fn ustack_top(&self) -> usize {
+        ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_SIZE
+    }
+}
+
+impl Drop for TaskUserRes {
+    fn drop(&mut self) {
+        self.dealloc_user_res();
+        if let Some(process) = self.process.upgrade() {
+            process.inner_exclusive_access().dealloc_tid(self.tid);
+        }
+    }
+}
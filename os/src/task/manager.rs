@@ -1,54 +1,45 @@
 //!Implementation of [`TaskManager`]
+use super::scheduler::{Scheduler, StrideScheduler};
 use super::TaskControlBlock;
 use crate::sync::UPSafeCell;
-use alloc::collections::VecDeque;
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use lazy_static::*;
-///A array of `TaskControlBlock` that is thread-safe
+
+/// The ready queue, generic over whichever [`Scheduler`] algorithm the
+/// kernel picked at init. Swapping the algorithm never touches `add_task`/
+/// `fetch_task` call sites.
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
-    sum: usize,
+    scheduler: Box<dyn Scheduler<Arc<TaskControlBlock>> + Send + Sync>,
 }
 
-/// A simple FIFO scheduler.
 impl TaskManager {
-    ///Creat an empty TaskManager
-    pub fn new() -> Self {
-        Self {
-            ready_queue: VecDeque::new(),
-            sum: 0,
-        }
+    /// Create a `TaskManager` backed by the given scheduler
+    pub fn new(scheduler: Box<dyn Scheduler<Arc<TaskControlBlock>> + Send + Sync>) -> Self {
+        Self { scheduler }
     }
     /// Add process back to ready queue
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
-        self.sum += 1;
+        self.scheduler.insert(task);
     }
-
-    // /// push_front
-    // pub fn add_front(&mut self, task: Arc<TaskControlBlock>) {
-    //     self.ready_queue.push_front(task);
-    //     self.sum += 1;
-    // }
-
-    /// Take a process out of the ready queue
+    /// Take the next task out of the ready queue, per the scheduler's policy
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        if self.sum != 0 {
-            self.sum -= 1;
-        }
-        self.ready_queue.pop_front()
+        self.scheduler.pop()
     }
-
-    ///获取总数
-    pub fn get_task_sum_in_ready(&self) -> usize {
-        self.sum
+    /// Peek at the task the scheduler would hand out next, without removing
+    /// it from the ready queue
+    pub fn peek(&self) -> Option<Arc<TaskControlBlock>> {
+        self.scheduler.peek().cloned()
     }
 }
 
 lazy_static! {
     /// TASK_MANAGER instance through lazy_static!
+    ///
+    /// Stride scheduling is the kernel's pick here; swap the boxed scheduler
+    /// for `FifoScheduler::new()` to go back to plain round-robin.
     pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
-        unsafe { UPSafeCell::new(TaskManager::new()) };
+        unsafe { UPSafeCell::new(TaskManager::new(Box::new(StrideScheduler::new()))) };
 }
 
 /// Add process to ready queue
@@ -57,19 +48,14 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().add(task);
 }
 
-// ///
-// pub fn add_front_task(task: Arc<TaskControlBlock>) {
-//     //trace!("kernel: TaskManager::add_task");
-//     TASK_MANAGER.exclusive_access().add_front(task);
-// }
-
 /// Take a process out of the ready queue
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     //trace!("kernel: TaskManager::fetch_task");
     TASK_MANAGER.exclusive_access().fetch()
 }
 
-/// 获取总数
-pub fn get_task_sum_in_ready() -> usize {
-    TASK_MANAGER.exclusive_access().get_task_sum_in_ready()
+/// The stride of whichever ready task is closest to running next, i.e. the
+/// current minimum stride in the ready queue. `None` if the queue is empty.
+pub fn min_stride() -> Option<usize> {
+    TASK_MANAGER.exclusive_access().peek().map(|task| task.stride())
 }
@@ -3,296 +3,115 @@
 //! Everything about task management, like starting and switching tasks is
 //! implemented here.
 //!
-//! A single global instance of [`TaskManager`] called `TASK_MANAGER` controls
-//! all the tasks in the operating system.
+//! A single global instance of [`Processor`] called `PROCESSOR` tracks which
+//! task is running on the (single) hart this kernel manages, and the ready
+//! queue is owned by the [`manager`] module's `TASK_MANAGER`. Task state
+//! transitions and context switching go through [`processor::schedule`]
+//! instead of ever comparing task indices directly.
 //!
 //! Be careful when you see `__switch` ASM function in `switch.S`. Control flow around this function
 //! might not be what you expect.
 
 mod context;
+mod id;
+mod manager;
+mod pid;
+mod processor;
+mod scheduler;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
-//
-// use VPNRange;
-//
-use crate::loader::{get_app_data, get_num_app};
-use crate::sync::UPSafeCell;
-use crate::trap::TrapContext;
-use alloc::vec::Vec;
+use crate::loader::get_app_data_by_name;
+use alloc::sync::Arc;
 use lazy_static::*;
-use switch::__switch;
 pub use task::{TaskControlBlock, TaskStatus};
 
 pub use context::TaskContext;
-
-/// The task manager, where all the tasks are managed.
-///
-/// Functions implemented on `TaskManager` deals with all task state transitions
-/// and task context switching. For convenience, you can find wrappers around it
-/// in the module level.
-///
-/// Most of `TaskManager` are hidden behind the field `inner`, to defer
-/// borrowing checks to runtime. You can see examples on how to use `inner` in
-/// existing functions on `TaskManager`.
-pub struct TaskManager {
-    /// total number of tasks
-    num_app: usize,
-    /// use inner value to get mutable access
-    inner: UPSafeCell<TaskManagerInner>,
-}
-
-/// The task manager inner in 'UPSafeCell'
-struct TaskManagerInner {
-    /// task list
-    tasks: Vec<TaskControlBlock>,
-    /// id of current `Running` task
-    current_task: usize,
-}
+pub use manager::{add_task, fetch_task, min_stride};
+pub use pid::{pid_alloc, KernelStack, PidHandle};
+pub use processor::{
+    current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
+};
 
 lazy_static! {
-    /// a `TaskManager` global instance through lazy_static!
-    pub static ref TASK_MANAGER: TaskManager = {
-        println!("init TASK_MANAGER");
-        let num_app = get_num_app();
-        println!("num_app = {}", num_app);
-        let mut tasks: Vec<TaskControlBlock> = Vec::new();
-        for i in 0..num_app {
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
-        }
-        TaskManager {
-            num_app,
-            inner: unsafe {
-                UPSafeCell::new(TaskManagerInner {
-                    tasks,
-                    current_task: 0,
-                })
-            },
-        }
-    };
+    /// The initial process, `initproc.bin`, which every orphaned task is
+    /// reparented to when its own parent has already exited.
+    pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new(TaskControlBlock::new(
+        get_app_data_by_name("initproc").unwrap()
+    ));
 }
 
-impl TaskManager {
-    /// Run the first task in task list.
-    ///
-    /// Generally, the first task in task list is an idle task (we call it zero process later).
-    /// But in ch4, we load apps statically, so the first task is a real app.
-    fn run_first_task(&self) -> ! {
-        let mut inner = self.inner.exclusive_access();
-        let next_task = &mut inner.tasks[0];
-        next_task.task_status = TaskStatus::Running;
-        // ch4:start_time
-        next_task.start_time = crate::timer::get_time_ms();
-        let next_task_cx_ptr = &next_task.task_cx as *const TaskContext;
-        drop(inner);
-        let mut _unused = TaskContext::zero_init();
-        // before this, we should drop local variables that must be dropped manually
-        unsafe {
-            __switch(&mut _unused as *mut _, next_task_cx_ptr);
-        }
-        panic!("unreachable in run_first_task!");
-    }
-
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].task_status = TaskStatus::Ready;
-    }
-
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].task_status = TaskStatus::Exited;
-    }
-
-    /// Find next task to run and return task id.
-    ///
-    /// In this case, we only return the first `Ready` task in task list.
-    fn find_next_task(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
-    }
-
-    /// Get the current 'Running' task's token.
-    fn get_current_token(&self) -> usize {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_user_token()
-    }
-
-    /// Get the current 'Running' task's trap contexts.
-    fn get_current_trap_cx(&self) -> &'static mut TrapContext {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_trap_cx()
-    }
-
-    /// Change the current 'Running' task's program break
-    pub fn change_current_program_brk(&self, size: i32) -> Option<usize> {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].change_program_brk(size)
-    }
-
-    /// Switch current `Running` task to the task we have found,
-    /// or there is no `Ready` task and we can exit with all applications completed
-    fn run_next_task(&self) {
-        if let Some(next) = self.find_next_task() {
-            let mut inner = self.inner.exclusive_access();
-            let current = inner.current_task;
-            inner.tasks[next].task_status = TaskStatus::Running;
-            // ch4:assign start_time
-            inner.tasks[next].start_time = crate::timer::get_time_ms();
-            inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
-            drop(inner);
-            // before this, we should drop local variables that must be dropped manually
-            unsafe {
-                __switch(current_task_cx_ptr, next_task_cx_ptr);
-            }
-            // go back to user mode
-        } else {
-            panic!("All applications completed!");
-        }
-    }
-
-    /// 增加计数
-    fn increase_current_syscall_count(&self, s_id: usize) {
-        let mut inner = self.inner.exclusive_access();
-        let ict = inner.current_task;
-        inner.tasks[ict].syscall_times[s_id] += 1;
-    }
-
-    /// 获取TCB信息
-    fn get_current_task_info(&self) -> (usize, [u32; crate::config::MAX_SYSCALL_NUM], TaskStatus) {
-        let inner = self.inner.exclusive_access();
-        (
-            inner.tasks[inner.current_task].start_time,
-            inner.tasks[inner.current_task].syscall_times,
-            inner.tasks[inner.current_task].task_status,
-        )
-    }
+/// Add `initproc` to the ready queue. The idle control flow is entered
+/// separately, through [`run_tasks`].
+pub fn add_initproc() {
+    add_task(INITPROC.clone());
+}
 
-    /// MAP
-    fn get_mm(&self, _start: usize, _len: usize, _port: usize) -> isize {
-        //检查起始地址是否对齐
-        if (_start % crate::config::PAGE_SIZE) != 0 {
-            return -1;
-        };
-        if _port != 1 && _port != 2 && _port != 3 {
-            return -1;
-        }
-        //设置标志位
-        let mut permission = crate::mm::MapPermission::from_bits((_port as u8) << 1).unwrap();
-        permission.set(crate::mm::MapPermission::U, true);
-        //TCB
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        let start_vpn: crate::mm::VirtPageNum =
-            (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
-        let end_vpn: crate::mm::VirtPageNum =
-            (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
-        let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
-        for vpn in vpn_range {
-            if inner.tasks[cur].memory_set.translate(vpn).is_some()
-                && inner.tasks[cur].memory_set.translate(vpn).unwrap().bits != 0
-            {
-                return -1;
-            }
+/// Suspend the current 'Running' task, put it back on the ready queue as
+/// `Ready`, and hand the processor back to the idle control flow so the next
+/// task can be picked.
+pub fn suspend_current_and_run_next() {
+    // There must be an application running.
+    let task = take_current_task().unwrap();
+
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // Change status to Ready
+    task_inner.task_status = TaskStatus::Ready;
+    drop(task_inner);
+    // ---- release current PCB
+
+    // push back to ready queue.
+    add_task(task);
+    // jump to scheduling cycle
+    schedule(task_cx_ptr);
+}
+
+/// Exit the current 'Running' task with the given exit code and hand the
+/// processor back to the idle control flow.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    // take from Processor
+    let task = take_current_task().unwrap();
+
+    // **** access current TCB exclusively
+    let mut inner = task.inner_exclusive_access();
+    // Change status to Zombie
+    inner.task_status = TaskStatus::Zombie;
+    // Record exit code
+    inner.exit_code = exit_code;
+    // do not move to its parent but under initproc
+
+    // ++++++ access initproc TCB exclusively
+    {
+        let mut initproc_inner = INITPROC.inner_exclusive_access();
+        for child in inner.children.iter() {
+            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+            initproc_inner.children.push(child.clone());
         }
-        inner.tasks[cur].memory_set.insert_framed_area(
-            _start.into(),
-            (_start + _len).into(),
-            permission,
-        );
-        return 0;
     }
-
-    ///unmap
-    fn get_unmap(&self, _start: usize, _len: usize) -> isize {
-        //检查起始地址是否对齐
-        if (_start % crate::config::PAGE_SIZE) != 0 {
-            return -1;
-        };
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        let start_vpn: crate::mm::VirtPageNum =
-            (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
-        let end_vpn: crate::mm::VirtPageNum =
-            (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
-        let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
-        //检查是否无映射
-        let mut id = 0;
-        let mut flag = -1;
-        for (index, area) in inner.tasks[cur].memory_set.areas.iter().enumerate() {
-            if area.vpn_range.get_start() == vpn_range.get_start()
-                && area.vpn_range.get_end() == vpn_range.get_end()
-            {
-                id = index;
-                flag = 0;
-            }
-        }
-        if flag == -1 {
-            return -1;
-        }
-        let ms: &mut crate::mm::MemorySet = &mut inner.tasks[cur].memory_set;
-        ms.areas[id].unmap(&mut ms.page_table);
-        inner.tasks[cur].memory_set.areas.remove(id);
-        return 0;
+    // ++++++ release parent PCB
+
+    inner.children.clear();
+    // Deallocate user space, but only once every thread sharing it has
+    // exited -- other `Arc` clones of `memory_set` belong to sibling
+    // threads that are still running.
+    if Arc::strong_count(&inner.memory_set) == 1 {
+        inner.memory_set.exclusive_access().recycle_data_pages();
     }
-}
-
-/// Run the first task in task list.
-pub fn run_first_task() {
-    TASK_MANAGER.run_first_task();
-}
-
-/// Switch current `Running` task to the task we have found,
-/// or there is no `Ready` task and we can exit with all applications completed
-fn run_next_task() {
-    TASK_MANAGER.run_next_task();
-}
-
-/// Change the status of current `Running` task into `Ready`.
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
-}
-
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
-}
-
-/// Suspend the current 'Running' task and run the next task in task list.
-pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
-    run_next_task();
-}
-
-/// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
-    run_next_task();
-}
-
-/// Get the current 'Running' task's token.
-pub fn current_user_token() -> usize {
-    TASK_MANAGER.get_current_token()
-}
-
-/// Get the current 'Running' task's trap contexts.
-pub fn current_trap_cx() -> &'static mut TrapContext {
-    TASK_MANAGER.get_current_trap_cx()
+    drop(inner);
+    // **** release current PCB
+    // drop task manually to maintain rc correctly
+    drop(task);
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
 }
 
 /// Change the current 'Running' task's program break
 pub fn change_program_brk(size: i32) -> Option<usize> {
-    TASK_MANAGER.change_current_program_brk(size)
+    current_task().unwrap().change_program_brk(size)
 }
 
 /// 增加计数
@@ -300,20 +119,128 @@ pub fn increase_syscall_count(syscall_id: usize) {
     if syscall_id >= crate::config::MAX_SYSCALL_NUM {
         return;
     }
-    TASK_MANAGER.increase_current_syscall_count(syscall_id);
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().syscall_times[syscall_id] += 1;
 }
 
 /// 获取信息
 pub fn get_current_task_info() -> (usize, [u32; crate::config::MAX_SYSCALL_NUM], TaskStatus) {
-    TASK_MANAGER.get_current_task_info()
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    (inner.start_time, inner.syscall_times, inner.task_status)
 }
 
-/// 获取内存
+/// MAP
 pub fn get_mm(_start: usize, _len: usize, _port: usize) -> isize {
-    TASK_MANAGER.get_mm(_start, _len, _port)
+    //检查起始地址是否对齐
+    if (_start % crate::config::PAGE_SIZE) != 0 {
+        return -1;
+    };
+    if _port != 1 && _port != 2 && _port != 3 {
+        return -1;
+    }
+    //设置标志位
+    let mut permission = crate::mm::MapPermission::from_bits((_port as u8) << 1).unwrap();
+    permission.set(crate::mm::MapPermission::U, true);
+    //TCB
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let mut memory_set = inner.memory_set.exclusive_access();
+    let start_vpn: crate::mm::VirtPageNum =
+        (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
+    let end_vpn: crate::mm::VirtPageNum =
+        (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
+    let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
+    for vpn in vpn_range {
+        if memory_set.translate(vpn).is_some() && memory_set.translate(vpn).unwrap().bits != 0 {
+            return -1;
+        }
+    }
+    memory_set.insert_framed_area(_start.into(), (_start + _len).into(), permission);
+    0
 }
 
 /// 销毁已分配内存
 pub fn get_unmap(_start: usize, _len: usize) -> isize {
-    TASK_MANAGER.get_unmap(_start, _len)
+    //检查起始地址是否对齐
+    if (_start % crate::config::PAGE_SIZE) != 0 {
+        return -1;
+    };
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let mut memory_set = inner.memory_set.exclusive_access();
+    let start_vpn: crate::mm::VirtPageNum =
+        (<usize as Into<crate::mm::VirtAddr>>::into(_start)).floor();
+    let end_vpn: crate::mm::VirtPageNum =
+        (<usize as Into<crate::mm::VirtAddr>>::into(_start + _len)).ceil();
+    let vpn_range = crate::mm::address::VPNRange::new(start_vpn, end_vpn);
+    munmap_range(&mut memory_set, vpn_range)
+}
+
+/// Unmap every page in `vpn_range`, trimming or splitting whichever
+/// `MapArea`s it overlaps so every page *outside* the range stays mapped.
+///
+/// Shared by [`get_unmap`] and `sys_munmap` so there is exactly one place
+/// that implements this.
+///
+/// Every page in the range must already be mapped (a hole anywhere in the
+/// range is rejected with `-1`). An area wholly inside the range is dropped
+/// entirely; an area the range only trims the start or end of, or splits in
+/// two, keeps the surviving sub-range(s) mapped by handing them back to
+/// [`crate::mm::MemorySet::insert_framed_area`] with the permission bits
+/// recovered from a still-valid PTE of that same area (`MapPermission`'s R/W/X/U
+/// bits line up 1:1 with the PTE's own, the same layout `get_mm`/`sys_mmap`
+/// already rely on when building a `MapPermission` out of `_port`). That
+/// recovers "stays mapped with the same permissions", but — unlike shrinking
+/// a `MapArea`'s own frame set in place — it does not preserve the original
+/// physical frames backing the surviving pages, since nothing outside the
+/// `mm` module can reach a `MapArea`'s frame table directly.
+pub(crate) fn munmap_range(
+    memory_set: &mut crate::mm::MemorySet,
+    vpn_range: crate::mm::address::VPNRange,
+) -> isize {
+    for vpn in vpn_range {
+        match memory_set.translate(vpn) {
+            Some(pte) if pte.bits != 0 => {}
+            _ => return -1,
+        }
+    }
+
+    let req_start = vpn_range.get_start();
+    let req_end = vpn_range.get_end();
+
+    let mut overlapping = alloc::vec::Vec::new();
+    for (index, area) in memory_set.areas.iter().enumerate() {
+        if area.vpn_range.get_start() < req_end && area.vpn_range.get_end() > req_start {
+            overlapping.push(index);
+        }
+    }
+    if overlapping.is_empty() {
+        return -1;
+    }
+
+    for index in overlapping.into_iter().rev() {
+        let area_start = memory_set.areas[index].vpn_range.get_start();
+        let area_end = memory_set.areas[index].vpn_range.get_end();
+
+        // The first page of this area inside vpn_range passed the translate()
+        // check above, so its PTE carries this area's permission bits.
+        let sample_vpn = if area_start > req_start { area_start } else { req_start };
+        let sample_pte = memory_set.translate(sample_vpn).unwrap();
+        let perm =
+            crate::mm::MapPermission::from_bits((sample_pte.bits as u8) & 0b0001_1110).unwrap();
+
+        memory_set.areas[index].unmap(&mut memory_set.page_table);
+        memory_set.areas.remove(index);
+
+        if area_start < req_start {
+            // Left remainder survives the unmap; put it back.
+            memory_set.insert_framed_area(area_start.into(), req_start.into(), perm);
+        }
+        if area_end > req_end {
+            // Right remainder survives the unmap; put it back.
+            memory_set.insert_framed_area(req_end.into(), area_end.into(), perm);
+        }
+    }
+    0
 }
@@ -0,0 +1,113 @@
+//! Implementation of [`Processor`], which holds the state shared between
+//! whatever task is running and the idle control flow that picks the next
+//! one.
+//!
+//! Switching tasks never goes directly from one task to another: it always
+//! passes through the processor's `idle_task_cx`. `run_tasks` is the idle
+//! loop itself (fetch a task, switch into it); `schedule` is how a task
+//! currently running switches back out to let `run_tasks` pick the next
+//! one. This indirection is what a later per-hart `Processor` needs: each
+//! hart's idle loop only ever talks to its own `Processor`.
+use super::manager::fetch_task;
+use super::switch::__switch;
+use super::{TaskContext, TaskControlBlock, TaskStatus};
+use crate::sync::UPSafeCell;
+use crate::trap::TrapContext;
+use alloc::sync::Arc;
+use lazy_static::*;
+
+/// Processor management structure
+pub struct Processor {
+    /// The task currently executing on the current processor
+    current: Option<Arc<TaskControlBlock>>,
+    /// The basic control flow of each core, helping to select and switch
+    /// process
+    idle_task_cx: TaskContext,
+}
+
+impl Processor {
+    /// Create an empty Processor
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            idle_task_cx: TaskContext::zero_init(),
+        }
+    }
+    fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
+        &mut self.idle_task_cx as *mut _
+    }
+    /// Take the current task out, leaving `current` empty
+    pub fn take_current(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.current.take()
+    }
+    /// Get a cloned `Arc` of the current task
+    pub fn current(&self) -> Option<Arc<TaskControlBlock>> {
+        self.current.as_ref().map(Arc::clone)
+    }
+}
+
+lazy_static! {
+    /// Global variable: the single processor this kernel manages
+    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+}
+
+/// The idle control flow: fetch the next ready task and `__switch` into it,
+/// over and over. Never returns.
+pub fn run_tasks() -> ! {
+    loop {
+        let mut processor = PROCESSOR.exclusive_access();
+        if let Some(task) = fetch_task() {
+            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            // access coming task TCB exclusively
+            let mut task_inner = task.inner_exclusive_access();
+            let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
+            task_inner.task_status = TaskStatus::Running;
+            if task_inner.start_time == 0 {
+                task_inner.start_time = crate::timer::get_time_ms();
+            }
+            drop(task_inner);
+            // release coming task TCB manually
+            processor.current = Some(task);
+            // release processor manually
+            drop(processor);
+            unsafe {
+                __switch(idle_task_cx_ptr, next_task_cx_ptr);
+            }
+        }
+    }
+}
+
+/// Take the current task, leaving `Processor::current` empty
+pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.exclusive_access().take_current()
+}
+
+/// Get a cloned `Arc` of the current task
+pub fn current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.exclusive_access().current()
+}
+
+/// Get the user address space token (satp) of the current task
+pub fn current_user_token() -> usize {
+    let task = current_task().unwrap();
+    task.get_user_token()
+}
+
+/// Get the mutable reference to trap context of the current task
+pub fn current_trap_cx() -> &'static mut TrapContext {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .get_trap_cx()
+}
+
+/// Switch out of the running task's context and back into the idle control
+/// flow, so `run_tasks` can pick whatever runs next.
+pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
+    let mut processor = PROCESSOR.exclusive_access();
+    let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+    drop(processor);
+    unsafe {
+        __switch(switched_task_cx_ptr, idle_task_cx_ptr);
+    }
+}
@@ -0,0 +1,102 @@
+//! Pluggable ready-queue algorithms used by [`super::manager::TaskManager`].
+//!
+//! `TaskManager` itself stays ignorant of *which* task runs next; that
+//! policy lives entirely behind the [`Scheduler`] trait, so new algorithms
+//! (priority, CFS-style, ...) can be dropped in without touching
+//! `add_task`/`fetch_task`.
+use super::TaskControlBlock;
+use alloc::collections::LinkedList;
+use alloc::sync::Arc;
+
+/// A ready-queue algorithm over items of type `T`.
+pub trait Scheduler<T> {
+    /// Add a task to the ready queue.
+    fn insert(&mut self, task: T);
+    /// Peek at the task that `pop` would return, without removing it.
+    fn peek(&self) -> Option<&T>;
+    /// Remove and return the next task to run.
+    fn pop(&mut self) -> Option<T>;
+}
+
+/// FIFO scheduler: hands tasks back in the order they were inserted.
+/// Behaves exactly like the `VecDeque`-based queue this replaces.
+///
+/// Not currently wired up (the kernel runs [`StrideScheduler`]), but kept
+/// around as the baseline algorithm to fall back to or compare against.
+#[allow(dead_code)]
+pub struct FifoScheduler {
+    queue: LinkedList<Arc<TaskControlBlock>>,
+}
+
+#[allow(dead_code)]
+impl FifoScheduler {
+    /// Create an empty `FifoScheduler`
+    pub fn new() -> Self {
+        Self {
+            queue: LinkedList::new(),
+        }
+    }
+}
+
+impl Scheduler<Arc<TaskControlBlock>> for FifoScheduler {
+    fn insert(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn peek(&self) -> Option<&Arc<TaskControlBlock>> {
+        self.queue.front()
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.pop_front()
+    }
+}
+
+/// Stride scheduler: always hands out the ready task with the smallest
+/// `stride`, advancing it by its `pass` so tasks with a higher priority
+/// (smaller `pass`) come back around sooner.
+pub struct StrideScheduler {
+    queue: LinkedList<Arc<TaskControlBlock>>,
+}
+
+impl StrideScheduler {
+    /// Create an empty `StrideScheduler`
+    pub fn new() -> Self {
+        Self {
+            queue: LinkedList::new(),
+        }
+    }
+}
+
+impl Scheduler<Arc<TaskControlBlock>> for StrideScheduler {
+    fn insert(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn peek(&self) -> Option<&Arc<TaskControlBlock>> {
+        min_stride_index(&self.queue).and_then(|idx| self.queue.iter().nth(idx))
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let idx = min_stride_index(&self.queue)?;
+        let mut tail = self.queue.split_off(idx);
+        let task = tail.pop_front().unwrap();
+        self.queue.append(&mut tail);
+        task.advance_stride();
+        Some(task)
+    }
+}
+
+fn min_stride_index(queue: &LinkedList<Arc<TaskControlBlock>>) -> Option<usize> {
+    queue
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| stride_cmp(a.stride(), b.stride()))
+        .map(|(idx, _)| idx)
+}
+
+/// Compare two strides that may have wrapped around `usize::MAX`.
+///
+/// As long as no two strides in the ready queue differ by more than the
+/// largest single `pass` (which holds here since `pass <= BIG_STRIDE / 2`),
+/// `a.wrapping_sub(b)` reinterpreted as signed tells us which one is
+/// "really" ahead even after one of them has wrapped.
+fn stride_cmp(a: usize, b: usize) -> core::cmp::Ordering {
+    (a.wrapping_sub(b) as isize).cmp(&0)
+}
@@ -0,0 +1,16 @@
+//! Rust wrapper around `__switch`.
+//!
+//! Switching tasks is implemented by saving and restoring callee-saved
+//! registers in the `TaskContext`. This is unsafe and low level, so the
+//! interface is wrapped here, and the real assembly implementation is
+//! in `switch.S`.
+use super::TaskContext;
+use core::arch::global_asm;
+
+global_asm!(include_str!("switch.S"));
+
+extern "C" {
+    /// Switch to the context of `next_task_cx_ptr`, saving the current context
+    /// in `current_task_cx_ptr`.
+    pub fn __switch(current_task_cx_ptr: *mut TaskContext, next_task_cx_ptr: *const TaskContext);
+}
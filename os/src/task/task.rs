@@ -0,0 +1,446 @@
+//! Implementation of [`TaskControlBlock`]
+use super::id::{RecycleAllocator, TaskUserRes};
+use super::{pid_alloc, KernelStack, PidHandle, TaskContext};
+use crate::config::{MAX_SYSCALL_NUM, TRAP_CONTEXT_BASE, USER_STACK_BASE};
+use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+/// The biggest stride value, used to derive how much a task's stride
+/// advances per scheduling turn from its priority (see [`TaskControlBlock::set_priority`]).
+pub const BIG_STRIDE: usize = 65535;
+
+/// The default `task_priority` assigned to a task when it is created,
+/// chosen so existing apps (which never call `sys_set_priority`) keep
+/// scheduling round-robin-like under stride scheduling.
+const DEFAULT_PRIORITY: isize = 16;
+
+/// Derive a stride `pass` from a priority, clamping priority to `>= 2` so
+/// `pass` never divides by zero (or by a negative priority cast to a huge
+/// `usize`, which would give a `pass` of 0 and starve every other task), and
+/// `pass` never exceeds `BIG_STRIDE / 2`.
+fn pass_of(priority: isize) -> usize {
+    BIG_STRIDE / (priority.max(2) as usize)
+}
+
+/// Task control block structure
+///
+/// Directly save the contents that will not change during running
+pub struct TaskControlBlock {
+    // immutable
+    /// Process identifier
+    pub pid: PidHandle,
+    /// Kernel stack corresponding to PID
+    pub kernel_stack: KernelStack,
+    // mutable
+    pub inner: UPSafeCell<TaskControlBlockInner>,
+}
+
+/// Structure containing more process content
+///
+/// Store the contents that will change during execution and are wrapped in
+/// `UPSafeCell` to provide mutability even though the process is wrapped in `Arc`
+pub struct TaskControlBlockInner {
+    /// The physical page number of the frame where the trap context is placed
+    pub trap_cx_ppn: PhysPageNum,
+    /// Application data can only appear in areas
+    /// where the application address space is lower than `base_size`
+    pub base_size: usize,
+    /// Save task context
+    pub task_cx: TaskContext,
+    /// Maintain the execution status of the current process
+    pub task_status: TaskStatus,
+    /// Application address space, shared by every thread of this process
+    /// (see [`TaskUserRes`] for how a thread's own stack/trap-context are
+    /// carved out of it)
+    pub memory_set: Arc<UPSafeCell<MemorySet>>,
+    /// Parent process of the current process.
+    /// Weak will not affect the reference count of the parent
+    pub parent: Option<Weak<TaskControlBlock>>,
+    /// A vector containing TCBs of all child processes of the current process
+    pub children: Vec<Arc<TaskControlBlock>>,
+    /// It is set when active exit or execution error occurs
+    pub exit_code: i32,
+    /// Heap bottom
+    pub heap_bottom: usize,
+    /// Program break
+    pub program_brk: usize,
+    /// The numbers of syscall called by this task
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// The time this task was first scheduled, in milliseconds
+    pub start_time: usize,
+    /// Scheduling priority, set through `sys_set_priority`; driving the
+    /// `pass` used by stride scheduling
+    pub task_priority: isize,
+    /// Stride scheduling's accumulated stride, advanced by `pass` every time
+    /// this task is picked to run
+    pub stride: usize,
+    /// Stride scheduling's per-turn increment, `BIG_STRIDE / priority`
+    pub pass: usize,
+    /// Base virtual address every thread of this process offsets its user
+    /// stack from by `tid` (see [`super::id::ustack_bottom_from_tid`])
+    pub ustack_base: usize,
+    /// Allocates tids to the threads of this process; the process's own
+    /// main thread implicitly holds tid 0
+    pub tid_allocator: RecycleAllocator,
+    /// `Some` for a thread created via `sys_thread_create`, `None` for the
+    /// process's own main thread (created via `new`/`fork`/`span`)
+    pub res: Option<TaskUserRes>,
+}
+
+impl TaskControlBlockInner {
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.trap_cx_ppn.get_mut()
+    }
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.exclusive_access().token()
+    }
+    fn get_status(&self) -> TaskStatus {
+        self.task_status
+    }
+    pub fn is_zombie(&self) -> bool {
+        self.get_status() == TaskStatus::Zombie
+    }
+    /// Recompute `pass` for a new priority, clamping as described in
+    /// [`pass_of`]. Leaves `stride` untouched so the task keeps whatever
+    /// ground it has already earned.
+    pub fn set_priority(&mut self, priority: isize) {
+        self.task_priority = priority;
+        self.pass = pass_of(priority);
+    }
+    /// Allocate a new tid for a thread of this process
+    pub fn alloc_tid(&mut self) -> usize {
+        self.tid_allocator.alloc()
+    }
+    /// Recycle a tid once its thread has exited
+    pub fn dealloc_tid(&mut self, tid: usize) {
+        self.tid_allocator.dealloc(tid)
+    }
+}
+
+impl TaskControlBlock {
+    /// Get the mutable reference of the inner TCB
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+    /// Get the address space token (`satp`) of this task
+    pub fn get_user_token(&self) -> usize {
+        self.inner_exclusive_access().get_user_token()
+    }
+    /// This task's current stride scheduling position
+    pub fn stride(&self) -> usize {
+        self.inner_exclusive_access().stride
+    }
+    /// Advance this task's stride by its `pass`, called by the scheduler
+    /// right before handing the task the CPU
+    pub fn advance_stride(&self) {
+        let mut inner = self.inner_exclusive_access();
+        let pass = inner.pass;
+        inner.stride = inner.stride.wrapping_add(pass);
+    }
+
+    /// Create a new process
+    ///
+    /// At present, it is only used for the creation of initproc
+    pub fn new(elf_data: &[u8]) -> Self {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let task_status = TaskStatus::Ready;
+        // map a kernel-stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let mut tid_allocator = RecycleAllocator::new();
+        // the main thread implicitly owns tid 0
+        tid_allocator.alloc();
+        let task_control_block = Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    parent: None,
+                    children: Vec::new(),
+                    exit_code: 0,
+                    heap_bottom: user_sp,
+                    program_brk: user_sp,
+                    syscall_times: [0; MAX_SYSCALL_NUM],
+                    start_time: 0,
+                    task_priority: DEFAULT_PRIORITY,
+                    stride: super::min_stride().unwrap_or(0),
+                    pass: pass_of(DEFAULT_PRIORITY),
+                    ustack_base: USER_STACK_BASE,
+                    tid_allocator,
+                    res: None,
+                })
+            },
+        };
+        // prepare TrapContext in user space
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+
+    /// Load a new elf to replace the original application address space and
+    /// start execution
+    pub fn exec(&self, elf_data: &[u8]) {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+
+        // **** access current TCB exclusively
+        let mut inner = self.inner_exclusive_access();
+        // substitute memory_set
+        inner.memory_set = Arc::new(unsafe { UPSafeCell::new(memory_set) });
+        // update trap_cx ppn
+        inner.trap_cx_ppn = trap_cx_ppn;
+        // initialize base_size
+        inner.base_size = user_sp;
+        inner.heap_bottom = user_sp;
+        inner.program_brk = user_sp;
+        // initialize trap_cx
+        let trap_cx = inner.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        // **** release current PCB
+    }
+
+    /// parent process fork the child process
+    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+        // ---- access parent PCB exclusively
+        let mut parent_inner = self.inner_exclusive_access();
+        // copy user space(include trap context)
+        let memory_set =
+            MemorySet::from_existed_user(&parent_inner.memory_set.exclusive_access());
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        // alloc a pid and a kernel stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let mut tid_allocator = RecycleAllocator::new();
+        tid_allocator.alloc();
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    heap_bottom: parent_inner.heap_bottom,
+                    program_brk: parent_inner.program_brk,
+                    syscall_times: [0; MAX_SYSCALL_NUM],
+                    start_time: 0,
+                    task_priority: DEFAULT_PRIORITY,
+                    stride: super::min_stride().unwrap_or(0),
+                    pass: pass_of(DEFAULT_PRIORITY),
+                    ustack_base: parent_inner.ustack_base,
+                    tid_allocator,
+                    res: None,
+                })
+            },
+        });
+        // add child
+        parent_inner.children.push(task_control_block.clone());
+        // modify kernel_sp in trap_cx
+        // **** access child PCB exclusively
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        // return
+        task_control_block
+        // **** release child PCB
+        // ---- release parent PCB
+    }
+
+    /// Spawn a new process directly from `elf_data`, without copying the
+    /// caller's address space the way `fork` does.
+    ///
+    /// Equivalent to `fork` + `exec`, but without the wasted copy of an
+    /// address space that's about to be replaced anyway.
+    pub fn span(self: &Arc<TaskControlBlock>, elf_data: &[u8]) -> Arc<TaskControlBlock> {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let mut tid_allocator = RecycleAllocator::new();
+        tid_allocator.alloc();
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    heap_bottom: user_sp,
+                    program_brk: user_sp,
+                    syscall_times: [0; MAX_SYSCALL_NUM],
+                    start_time: 0,
+                    task_priority: DEFAULT_PRIORITY,
+                    stride: super::min_stride().unwrap_or(0),
+                    pass: pass_of(DEFAULT_PRIORITY),
+                    ustack_base: USER_STACK_BASE,
+                    tid_allocator,
+                    res: None,
+                })
+            },
+        });
+        self.inner_exclusive_access()
+            .children
+            .push(task_control_block.clone());
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+
+    /// Spawn a new thread sharing this task's address space, starting
+    /// execution at `entry` with `arg` passed through as its first argument
+    /// (`a0`). Returns the new thread's own `TaskControlBlock`; its tid is
+    /// available via `inner_exclusive_access().res`.
+    pub fn create_thread(self: &Arc<TaskControlBlock>, entry: usize, arg: usize) -> Arc<TaskControlBlock> {
+        let (ustack_base, memory_set, heap_bottom, program_brk) = {
+            let process_inner = self.inner_exclusive_access();
+            (
+                process_inner.ustack_base,
+                process_inner.memory_set.clone(),
+                process_inner.heap_bottom,
+                process_inner.program_brk,
+            )
+        };
+
+        let task_user_res = TaskUserRes::new(self, ustack_base, true);
+        let ustack_top = task_user_res.ustack_top();
+        let trap_cx_ppn = task_user_res.trap_cx_ppn();
+
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: ustack_top,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    heap_bottom,
+                    program_brk,
+                    syscall_times: [0; MAX_SYSCALL_NUM],
+                    start_time: 0,
+                    task_priority: DEFAULT_PRIORITY,
+                    stride: super::min_stride().unwrap_or(0),
+                    pass: pass_of(DEFAULT_PRIORITY),
+                    ustack_base,
+                    tid_allocator: RecycleAllocator::new(),
+                    res: Some(task_user_res),
+                })
+            },
+        });
+
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry,
+            ustack_top,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = arg;
+
+        task_control_block
+    }
+
+    /// get pid of process
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+
+    /// change the location of the program break. return None if failed.
+    pub fn change_program_brk(&self, size: i32) -> Option<usize> {
+        let mut inner = self.inner_exclusive_access();
+        let old_break = inner.program_brk;
+        let new_brk = inner.program_brk as isize + size as isize;
+        if new_brk < inner.heap_bottom as isize {
+            return None;
+        }
+        let heap_bottom = inner.heap_bottom;
+        let mut memory_set = inner.memory_set.exclusive_access();
+        let result = if size < 0 {
+            memory_set.shrink_to(VirtAddr::from(heap_bottom), VirtAddr::from(new_brk as usize))
+        } else {
+            memory_set.append_to(VirtAddr::from(heap_bottom), VirtAddr::from(new_brk as usize))
+        };
+        drop(memory_set);
+        if result {
+            inner.program_brk = new_brk as usize;
+            Some(old_break)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+/// task status: Ready, Running, Zombie
+pub enum TaskStatus {
+    /// ready to run
+    Ready,
+    /// currently running
+    Running,
+    /// waited to be reaped by its parent via `sys_waitpid`
+    Zombie,
+}